@@ -1,11 +1,68 @@
 use indicatif::ProgressBar;
 use jiff::{civil::Weekday, ToSpan};
-use rand::{rngs::ThreadRng, seq::IteratorRandom};
+use rand::{rngs::ThreadRng, seq::IteratorRandom, Rng};
+use rayon::prelude::*;
 use std::{collections::HashMap, error::Error, fs::File, io::BufReader, iter::zip};
 
 const DEFAULT_COUNT: usize = 1_000_000;
+/// Size of the reservoir kept per project when down-sampling simulation runs.
+const DEFAULT_RESERVOIR_SIZE: usize = 10_000;
 
-use serde::Deserialize;
+/// Configuration for stopping `montecarlo` early once the percentile estimates
+/// have converged, instead of always running a fixed number of simulations.
+#[derive(Debug, Clone, Copy)]
+pub struct ConvergenceConfig {
+    /// Number of simulations compared batch-to-batch before checking for convergence. This is
+    /// the exact KS sample size and is not clamped to the reservoir size; `montecarlo` still
+    /// materializes the underlying runs in reservoir-sized chunks internally to bound memory.
+    pub batch_size: usize,
+    /// Maximum Kolmogorov-Smirnov statistic between consecutive batches that still
+    /// counts as "stable".
+    pub epsilon: f32,
+    /// Number of consecutive stable batches required before stopping.
+    pub stable_batches: usize,
+    /// Hard cap on total iterations, regardless of whether convergence is reached.
+    pub max_iterations: usize,
+}
+
+impl Default for ConvergenceConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 10_000,
+            epsilon: 0.01,
+            stable_batches: 3,
+            max_iterations: DEFAULT_COUNT,
+        }
+    }
+}
+
+/// Computes the Kolmogorov-Smirnov statistic between two sorted samples: the
+/// maximum absolute difference between their empirical CDFs. A fresh project with
+/// no prior batch is treated as maximally unconverged.
+fn ks_statistic(a: &[f32], b: &[f32]) -> f32 {
+    if a.is_empty() || b.is_empty() {
+        return 1.0;
+    }
+    let (mut i, mut j) = (0usize, 0usize);
+    let mut max_diff = 0.0f32;
+    while i < a.len() && j < b.len() {
+        // Advance past every copy of the next threshold in both samples, so the CDFs are only
+        // compared at actual thresholds and never in the middle of a run of tied values.
+        let threshold = a[i].min(b[j]);
+        while i < a.len() && a[i] <= threshold {
+            i += 1;
+        }
+        while j < b.len() && b[j] <= threshold {
+            j += 1;
+        }
+        let fa = i as f32 / a.len() as f32;
+        let fb = j as f32 / b.len() as f32;
+        max_diff = max_diff.max((fa - fb).abs());
+    }
+    max_diff
+}
+
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Deserialize)]
 struct Task {
@@ -101,63 +158,506 @@ impl EBS {
         Ok(res)
     }
 
-    pub fn montecarlo(&mut self, count: Option<usize>, mut rng: &mut ThreadRng) -> Vec<Vec<f32>> {
-        let count = count.unwrap_or(DEFAULT_COUNT);
-        let pb = ProgressBar::new((count * self.projects.len()) as u64);
-        let step = count / 100;
-        let start = step - 1;
+    /// Runs Monte Carlo simulations, keeping at most `reservoir_size` (default
+    /// [`DEFAULT_RESERVOIR_SIZE`]) samples per project.
+    ///
+    /// Rather than collecting every run into an unbounded `Vec`, each project keeps a
+    /// fixed-size reservoir maintained online via Algorithm R: the first `reservoir_size`
+    /// runs are kept outright, and for the i-th run thereafter a uniformly random index
+    /// j in [0, i] is drawn, replacing reservoir[j] when j falls inside the reservoir. This
+    /// keeps peak memory at O(reservoir_size) per project instead of O(count) while still
+    /// producing a uniform random subset of all runs.
+    ///
+    /// If `convergence` is `None`, exactly `count` simulations are run (default
+    /// [`DEFAULT_COUNT`]). If `convergence` is `Some`, simulations instead run in batches of
+    /// `ConvergenceConfig::batch_size`: after each batch, that batch's own empirical CDF is
+    /// compared against the previous batch's via the Kolmogorov-Smirnov statistic, and once
+    /// that statistic stays below `epsilon` for `stable_batches` batches in a row (or
+    /// `max_iterations` is reached) the run stops. `count` is ignored in that mode. Regardless
+    /// of batch size, runs are materialized internally in reservoir-sized chunks to keep peak
+    /// memory bounded.
+    pub fn montecarlo(
+        &mut self,
+        count: Option<usize>,
+        reservoir_size: Option<usize>,
+        convergence: Option<ConvergenceConfig>,
+        rng: &mut ThreadRng,
+    ) -> MonteCarloResult {
+        let k = reservoir_size.unwrap_or(DEFAULT_RESERVOIR_SIZE);
+        let max_iterations = convergence
+            .map(|c| c.max_iterations)
+            .unwrap_or_else(|| count.unwrap_or(DEFAULT_COUNT));
+        // The number of runs compared batch-to-batch for convergence. Left uncapped (not
+        // clamped to the reservoir size) so a caller-requested `ConvergenceConfig::batch_size`
+        // controls the KS sample size exactly, independent of how memory is bounded below. In
+        // non-convergence mode this spans the whole run.
+        let convergence_batch_size = convergence.map(|c| c.batch_size).unwrap_or(max_iterations);
+        // Regardless of `convergence_batch_size`, runs are materialized in chunks of at most
+        // the reservoir size, so peak memory stays O(reservoir_size) per project rather than
+        // O(convergence_batch_size) or O(count).
+        let materialize_chunk = k;
+        let pb = ProgressBar::new((max_iterations * self.projects.len()) as u64);
         pb.tick();
-        // We run {count} simulations
-        (0..count).for_each(|_| {
-            self.projects.iter().fold(0.0, |remaining, (_, id)| {
-                // The "montecarlo" here is randomly specifying that the 
-                // Task will take a previous velocity length
-                let task_estimates = self.todos[*id].clone();
-                let t = task_estimates.iter().fold(0.0, |estimate, t| {
-                    t / self.velocity.iter().choose(&mut rng).unwrap() + estimate
-                });
-                // And then that we will have a random buffer left after finishing the task
-                let time_remaining = t * self.buffer.iter().choose(&mut rng).unwrap() + remaining;
-                if let Some(exists) = self.simulation_runs.get_mut(*id) {
-                    exists.push(time_remaining);
-                } else {
-                    self.simulation_runs.push(vec![time_remaining]);
+
+        let mut iterations = 0usize;
+        let mut stable_batches = 0usize;
+        let mut previous_batch: Option<Vec<Vec<f32>>> = None;
+        'batches: loop {
+            if iterations >= max_iterations {
+                break 'batches;
+            }
+            let this_convergence_batch = convergence_batch_size.min(max_iterations - iterations);
+            // Only accumulated when convergence checking is active; in non-convergence mode
+            // each run only ever needs to reach the reservoir, not be held onto afterwards.
+            let mut batch_accum: Option<Vec<Vec<f32>>> = convergence
+                .map(|_| vec![Vec::with_capacity(this_convergence_batch); self.projects.len()]);
+
+            let mut processed = 0usize;
+            while processed < this_convergence_batch {
+                let chunk_size = materialize_chunk.min(this_convergence_batch - processed);
+                // Each of the `chunk_size` runs is independent, so compute them in parallel
+                // and only fold the results into the (inherently sequential) reservoirs
+                // afterwards.
+                let batch_results = self.run_batch(chunk_size);
+                for task_idx in 0..chunk_size {
+                    let i = iterations + processed + task_idx;
+                    for (id, project_results) in batch_results.iter().enumerate() {
+                        let time_remaining = project_results[task_idx];
+                        if let Some(accum) = batch_accum.as_mut() {
+                            accum[id].push(time_remaining);
+                        }
+                        let reservoir = if let Some(exists) = self.simulation_runs.get_mut(id) {
+                            exists
+                        } else {
+                            self.simulation_runs.push(Vec::with_capacity(k));
+                            self.simulation_runs.last_mut().unwrap()
+                        };
+                        if i < k {
+                            reservoir.push(time_remaining);
+                        } else {
+                            let j = rng.gen_range(0..=i);
+                            if j < k {
+                                reservoir[j] = time_remaining;
+                            }
+                        }
+                    }
                 }
-                time_remaining
-            });
-            pb.inc(1);
-        });
+                processed += chunk_size;
+                pb.inc((chunk_size * self.projects.len()) as u64);
+            }
+            iterations += this_convergence_batch;
+
+            let Some(config) = convergence else {
+                continue;
+            };
+            // Compare this batch's own empirical CDF against the previous batch's, not the
+            // cumulative reservoir's: the reservoir's replacement probability shrinks as the
+            // global index grows, so comparing reservoir snapshots would measure reservoir
+            // saturation rather than genuine convergence of the underlying distribution.
+            let current_batch: Vec<Vec<f32>> = batch_accum
+                .unwrap()
+                .into_iter()
+                .map(|mut samples| {
+                    samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                    samples
+                })
+                .collect();
+            if let Some(previous) = &previous_batch {
+                let max_ks = zip(previous, &current_batch)
+                    .map(|(prev, curr)| ks_statistic(prev, curr))
+                    .fold(0.0f32, f32::max);
+                stable_batches = if max_ks < config.epsilon {
+                    stable_batches + 1
+                } else {
+                    0
+                };
+            }
+            previous_batch = Some(current_batch);
+            if stable_batches >= config.stable_batches {
+                break;
+            }
+        }
         pb.finish_and_clear();
-        println!(
-            "Simulations ran for {} projects in {:?}.",
+        // Diagnostic, not part of the report: keep it off stdout so `--format json`/`table`
+        // output stays directly pipeable into e.g. `jq`.
+        eprintln!(
+            "Simulations ran for {} projects in {:?} ({} iterations).",
             self.projects.len(),
-            pb.elapsed()
+            pb.elapsed(),
+            iterations
         );
-        // We then trim down the simulation runs to 1/10th sampling
-        self.simulation_runs
+        let samples = self
+            .simulation_runs
             .iter_mut()
-            .map(|times| {
-                times.sort_by(|a, b| a.partial_cmp(b).unwrap());
-                times.iter().skip(start).step_by(step).copied().collect()
+            .map(|reservoir| {
+                reservoir.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                reservoir.clone()
+            })
+            .collect();
+        MonteCarloResult { samples, iterations }
+    }
+
+    /// Runs `batch_size` independent simulations in parallel via rayon, returning the raw
+    /// per-project results (indexed by project id, one value per task index within the batch).
+    ///
+    /// `velocity`, `buffer`, and `todos` are read-only for the duration of the batch, so each
+    /// rayon task borrows them directly and draws its own `rand::thread_rng()` rather than
+    /// sharing one across threads. Results are folded per-thread into a `Vec<Vec<f32>>` and then
+    /// reduced, avoiding a lock around a shared accumulator.
+    fn run_batch(&self, batch_size: usize) -> Vec<Vec<f32>> {
+        let mut ids: Vec<usize> = self.projects.values().copied().collect();
+        ids.sort_unstable();
+        let velocity = &self.velocity;
+        let buffer = &self.buffer;
+        let todos = &self.todos;
+
+        (0..batch_size)
+            .into_par_iter()
+            .map(|_| {
+                let mut rng = rand::thread_rng();
+                ids.iter()
+                    .fold((0.0f32, Vec::with_capacity(ids.len())), |(remaining, mut acc), &id| {
+                        // The "montecarlo" here is randomly specifying that the
+                        // Task will take a previous velocity length
+                        let t = todos[id].iter().fold(0.0, |estimate, task_estimate| {
+                            task_estimate / velocity.iter().choose(&mut rng).unwrap() + estimate
+                        });
+                        // And then that we will have a random buffer left after finishing the task
+                        let time_remaining = t * buffer.iter().choose(&mut rng).unwrap() + remaining;
+                        acc.push(time_remaining);
+                        (time_remaining, acc)
+                    })
+                    .1
+            })
+            .fold(
+                || vec![Vec::new(); ids.len()],
+                |mut acc, task_result| {
+                    for (slot, value) in acc.iter_mut().zip(task_result) {
+                        slot.push(value);
+                    }
+                    acc
+                },
+            )
+            .reduce(
+                || vec![Vec::new(); ids.len()],
+                |mut a, b| {
+                    for (slot_a, slot_b) in a.iter_mut().zip(b) {
+                        slot_a.extend(slot_b);
+                    }
+                    a
+                },
+            )
+    }
+
+    /// Bootstraps a 95% confidence interval around the `percentile` (0.0-100.0) of `samples`.
+    ///
+    /// `samples` need not be sorted. `replicates` resamples of `samples` (with replacement,
+    /// same length) are drawn, the target percentile is computed in each, and the 2.5th and
+    /// 97.5th percentiles of those replicate estimates become the interval bounds. This
+    /// quantifies how much a reported percentile should be trusted given how much simulation
+    /// data backs it.
+    pub fn bootstrap_percentile(
+        samples: &[f32],
+        percentile: f32,
+        replicates: usize,
+        rng: &mut ThreadRng,
+    ) -> PercentileEstimate {
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let value = percentile_of(&sorted, percentile);
+
+        let mut replicate_estimates: Vec<f32> = (0..replicates)
+            .map(|_| {
+                let mut resample: Vec<f32> = (0..samples.len())
+                    .map(|_| samples[rng.gen_range(0..samples.len())])
+                    .collect();
+                resample.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                percentile_of(&resample, percentile)
+            })
+            .collect();
+        replicate_estimates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        PercentileEstimate {
+            percentile,
+            value,
+            ci_low: percentile_of(&replicate_estimates, 2.5),
+            ci_high: percentile_of(&replicate_estimates, 97.5),
+        }
+    }
+
+    /// Computes the full empirical CDF of `samples`: each sorted value (duplicates included)
+    /// paired with the cumulative fraction of samples at or below it. Unlike [`Self::bootstrap_percentile`],
+    /// which only interpolates a handful of requested percentiles, this exposes every point for
+    /// plotting.
+    pub fn cdf(samples: &[f32]) -> Vec<CdfPoint> {
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        sorted
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| CdfPoint {
+                value,
+                probability: (i + 1) as f32 / sorted.len() as f32,
             })
             .collect()
     }
 }
 
+/// A single (value, cumulative-probability) point of an empirical CDF.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CdfPoint {
+    pub value: f32,
+    pub probability: f32,
+}
+
+/// A point estimate for a percentile, accompanied by the bootstrap confidence interval
+/// around it.
+#[derive(Debug, Clone, Copy)]
+pub struct PercentileEstimate {
+    pub percentile: f32,
+    pub value: f32,
+    pub ci_low: f32,
+    pub ci_high: f32,
+}
+
+/// Linearly interpolated percentile (0.0-100.0) of an already-sorted sample.
+fn percentile_of(sorted: &[f32], percentile: f32) -> f32 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (percentile / 100.0) * (sorted.len() - 1) as f32;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    if lower == upper {
+        sorted[lower]
+    } else {
+        let frac = rank - lower as f32;
+        sorted[lower] + frac * (sorted[upper] - sorted[lower])
+    }
+}
+
+/// Result of a [`EBS::montecarlo`] run: the sorted, reservoir-sampled completion
+/// times per project, along with how many simulations it actually took.
+#[derive(Debug, Clone)]
+pub struct MonteCarloResult {
+    /// Sorted reservoir of completion-time samples, indexed by project id.
+    pub samples: Vec<Vec<f32>>,
+    /// Total number of simulations actually performed.
+    pub iterations: usize,
+}
+
+/// Number of bootstrap replicates drawn when estimating a percentile's confidence interval.
+const BOOTSTRAP_REPLICATES: usize = 1000;
+/// Percentiles reported by default when no others are requested.
+const DEFAULT_PERCENTILES: [f32; 2] = [50.0, 95.0];
+
+/// A single percentile estimate for one project, in both dev-days and calendar-date form,
+/// ready to be serialized as JSON or rendered as text/table.
+#[derive(Debug, Clone, Serialize)]
+pub struct PercentileReport {
+    pub percentile: f32,
+    pub dev_days: f32,
+    pub date: String,
+    pub ci_low_date: String,
+    pub ci_high_date: String,
+}
+
+/// Every requested percentile estimate for a single project, plus its full empirical CDF
+/// when `--cdf` is requested.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProjectReport {
+    pub project: String,
+    pub percentiles: Vec<PercentileReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cdf: Option<Vec<CdfPoint>>,
+}
+
+/// The full output of a simulation run, across all projects, suitable for `--format json`
+/// or `--format table` as well as the default text rendering.
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulationReport {
+    pub projects: Vec<ProjectReport>,
+}
+
+impl SimulationReport {
+    /// Builds a report from a completed [`MonteCarloResult`], bootstrapping a confidence
+    /// interval around each of `percentiles` for every project, and attaching the full
+    /// empirical CDF per project when `include_cdf` is set.
+    fn from_result(
+        ebs: &EBS,
+        result: &MonteCarloResult,
+        percentiles: &[f32],
+        include_cdf: bool,
+        start_date: &jiff::Zoned,
+        rng: &mut ThreadRng,
+    ) -> Self {
+        let projects = ebs
+            .projects
+            .iter()
+            .map(|(project, id)| {
+                let samples = &result.samples[*id];
+                let percentiles = percentiles
+                    .iter()
+                    .map(|&p| {
+                        let estimate =
+                            EBS::bootstrap_percentile(samples, p, BOOTSTRAP_REPLICATES, rng);
+                        let dev_days = (estimate.value / 8.0).ceil();
+                        let ci_low = (estimate.ci_low / 8.0).ceil();
+                        let ci_high = (estimate.ci_high / 8.0).ceil();
+                        PercentileReport {
+                            percentile: p,
+                            dev_days,
+                            date: dev_days_as_days(dev_days as usize, start_date.clone())
+                                .to_string(),
+                            ci_low_date: dev_days_as_days(ci_low as usize, start_date.clone())
+                                .to_string(),
+                            ci_high_date: dev_days_as_days(ci_high as usize, start_date.clone())
+                                .to_string(),
+                        }
+                    })
+                    .collect();
+                ProjectReport {
+                    project: project.clone(),
+                    percentiles,
+                    cdf: include_cdf.then(|| EBS::cdf(samples)),
+                }
+            })
+            .collect();
+        SimulationReport { projects }
+    }
+}
+
+/// Output format selected via `--format` on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+    Table,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "table" => Ok(OutputFormat::Table),
+            other => Err(format!("unknown --format '{other}', expected text/json/table")),
+        }
+    }
+}
+
+fn print_text(report: &SimulationReport) {
+    for project in &report.projects {
+        println!("{}:", project.project);
+        for p in &project.percentiles {
+            println!(
+                "\t{}% chance: {}, {} dev days (CI {} - {})",
+                p.percentile, p.date, p.dev_days, p.ci_low_date, p.ci_high_date
+            );
+        }
+        if let Some(cdf) = &project.cdf {
+            println!("\tcdf:");
+            for point in cdf {
+                println!("\t\t{}\t{}", point.value, point.probability);
+            }
+        }
+    }
+}
+
+fn print_table(report: &SimulationReport) {
+    let Some(percentiles) = report.projects.first().map(|p| &p.percentiles) else {
+        return;
+    };
+    let mut header = vec!["project".to_string()];
+    header.extend(percentiles.iter().map(|p| format!("{}%", p.percentile)));
+    header.push("date".to_string());
+
+    let rows: Vec<Vec<String>> = report
+        .projects
+        .iter()
+        .map(|project| {
+            let mut row = vec![project.project.clone()];
+            row.extend(project.percentiles.iter().map(|p| p.dev_days.to_string()));
+            row.push(
+                project
+                    .percentiles
+                    .last()
+                    .map(|p| p.date.clone())
+                    .unwrap_or_default(),
+            );
+            row
+        })
+        .collect();
+
+    let mut widths: Vec<usize> = header.iter().map(|cell| cell.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let print_row = |row: &[String]| {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        println!("{}", cells.join("  "));
+    };
+    print_row(&header);
+    for row in &rows {
+        print_row(row);
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let mut rng = rand::thread_rng();
     let date = jiff::Zoned::now();
-    let args = std::env::args();
-    if let Some(tasks) = args.into_iter().nth(1) {
-        let mut ebs = EBS::new_from_file(tasks)?;
-        let _f = ebs.montecarlo(None, &mut rng);
-        ebs.projects.iter().for_each(|(project, id)| {
-            let chance50 = (_f[*id][49] / 8.0).ceil();
-            let chance95 = (_f[*id][94] / 8.0).ceil();
-            println!("{project}:");
-            println!("\t50% chance: {}, {} dev days", &dev_days_as_days(chance50 as usize, date.clone()), chance50);
-            println!("\t95% chance: {}, {} dev days", &dev_days_as_days(chance95 as usize, date.clone()), chance95);
-        })
+    let mut tasks_path = None;
+    let mut format = OutputFormat::Text;
+    let mut percentiles: Option<Vec<f32>> = None;
+    let mut include_cdf = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                let value = args.next().ok_or("--format requires a value")?;
+                format = value.parse()?;
+            }
+            "--percentiles" => {
+                let value = args.next().ok_or("--percentiles requires a value")?;
+                percentiles = Some(
+                    value
+                        .split(',')
+                        .map(|p| p.trim().parse::<f32>())
+                        .collect::<Result<Vec<f32>, _>>()?,
+                );
+            }
+            "--cdf" => include_cdf = true,
+            other if tasks_path.is_none() => tasks_path = Some(other.to_string()),
+            _ => {}
+        }
+    }
+    let Some(tasks) = tasks_path else {
+        return Ok(());
+    };
+    let percentiles = percentiles.unwrap_or_else(|| DEFAULT_PERCENTILES.to_vec());
+    let mut ebs = EBS::new_from_file(tasks)?;
+    let result = ebs.montecarlo(None, None, None, &mut rng);
+    let report = SimulationReport::from_result(
+        &ebs,
+        &result,
+        &percentiles,
+        include_cdf,
+        &date,
+        &mut rng,
+    );
+    match format {
+        OutputFormat::Text => print_text(&report),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        OutputFormat::Table => print_table(&report),
     }
     Ok(())
 }